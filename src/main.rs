@@ -20,12 +20,23 @@ extern crate clap;
 #[macro_use]
 extern crate log;
 extern crate hex;
+#[cfg(feature = "metrics")]
+extern crate prometheus;
 extern crate protobuf;
 extern crate sawtooth_sdk;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 extern crate simple_logger;
+#[cfg(feature = "metrics")]
+extern crate tiny_http;
+extern crate toml;
 
+use std::path::Path;
 use std::process;
+use std::time::Duration;
 
 use sawtooth_sdk::consensus::zmq_driver::ZmqDriver;
 use sawtooth_sdk::consensus::engine::Engine;
@@ -44,7 +55,21 @@ fn main() {
         (@arg verbose: -v --verbose +multiple
          "increase output verbosity")
         (@arg ID: +required "the PBFT node's id")
-        (@arg dead: -d +takes_value "simulate a dead node"))
+        (@arg dead: -d +takes_value "simulate a dead node")
+        (@arg config: --config +takes_value
+         "path to a TOML config file (peers, timeouts, checkpoint interval)")
+        (@arg data_dir: --("data-dir") +takes_value
+         "directory to load/generate this node's signing keypair into (default ./data)")
+        (@arg fault_tolerance: --("fault-tolerance") +takes_value
+         "override the maximum number of faulty nodes (f); takes precedence over --config")
+        (@arg view_change_timeout: --("view-change-timeout") +takes_value
+         "override the view change timeout, e.g. \"20s\"; takes precedence over --config")
+        (@arg checkpoint_interval: --("checkpoint-interval") +takes_value
+         "override the number of blocks between stable checkpoints; takes precedence over --config")
+        (@arg informant_interval: --("informant-interval") +takes_value
+         "seconds between status log lines (default 10)")
+        (@arg metrics: --metrics +takes_value
+         "address to serve Prometheus metrics on, e.g. 0.0.0.0:9090 (requires the \"metrics\" feature)"))
         .get_matches();
 
     let log_level = match matches.occurrences_of("verbose") {
@@ -65,6 +90,92 @@ fn main() {
 
     simple_logger::init_with_level(log_level).unwrap();
 
+    let config = node::config::PbftConfig::default_with_peers(Vec::new());
+    let mut config = match matches.value_of("config") {
+        Some(path) => node::config_file::load(Path::new(path), config).unwrap_or_else(|err| {
+            error!("{}", err);
+            process::exit(1);
+        }),
+        None => config,
+    };
+
+    // CLI flags always take precedence over whatever --config loaded
+    if let Some(fault_tolerance) = matches.value_of("fault_tolerance") {
+        config.fault_tolerance = Some(fault_tolerance.parse::<u64>().unwrap_or_else(|e| {
+            error!("Invalid --fault-tolerance: {}", e);
+            process::exit(1);
+        }));
+    }
+    if let Some(view_change_timeout) = matches.value_of("view_change_timeout") {
+        config.view_change_timeout = node::config_file::to_duration(view_change_timeout).unwrap_or_else(|err| {
+            error!("{}", err);
+            process::exit(1);
+        });
+    }
+    if let Some(checkpoint_interval) = matches.value_of("checkpoint_interval") {
+        config.checkpoint_interval = checkpoint_interval.parse::<u64>().unwrap_or_else(|e| {
+            error!("Invalid --checkpoint-interval: {}", e);
+            process::exit(1);
+        });
+    }
+
+    if config.peers.is_empty() {
+        error!(
+            "No peers configured; pass --config <path> with a non-empty peer table before starting"
+        );
+        process::exit(1);
+    }
+
+    let data_dir = Path::new(matches.value_of("data_dir").unwrap_or("./data"));
+    let own_peer_id_hex = config
+        .peers
+        .iter()
+        .find(|(_peer_id, node_id)| *node_id == id)
+        .map(|(peer_id, _node_id)| hex::encode(peer_id.as_slice()));
+    let key_manager =
+        node::crypto::PbftKeyManager::load_or_generate(id, data_dir, own_peer_id_hex.as_deref())
+            .unwrap_or_else(|err| {
+                error!("{}", err);
+                process::exit(1);
+            });
+
+    let informant_interval_secs = match matches.value_of("informant_interval") {
+        Some(s) => s.parse::<u64>().unwrap_or_else(|e| {
+            error!("Invalid --informant-interval: {}", e);
+            process::exit(1);
+        }),
+        None => 10,
+    };
+    let informant = node::informant::Informant::new(Duration::from_secs(informant_interval_secs));
+
+    // Built once and handed to the engine (so it can call `observe()` each loop iteration) as
+    // well as to the HTTP server (so it can render whatever the engine last observed).
+    #[cfg(feature = "metrics")]
+    let metrics: Option<::std::sync::Arc<node::metrics::PbftMetrics>> = match matches.value_of("metrics") {
+        Some(addr) => {
+            let addr = addr.parse().unwrap_or_else(|err| {
+                error!("Invalid --metrics address: {}", err);
+                process::exit(1);
+            });
+            let metrics = ::std::sync::Arc::new(node::metrics::PbftMetrics::new().unwrap_or_else(|err| {
+                error!("{}", err);
+                process::exit(1);
+            }));
+            node::metrics::serve(metrics.clone(), addr).unwrap_or_else(|err| {
+                error!("{}", err);
+                process::exit(1);
+            });
+            Some(metrics)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "metrics"))]
+    {
+        if matches.value_of("metrics").is_some() {
+            warn!("--metrics was given, but this binary was built without the \"metrics\" feature");
+        }
+    }
+
     warn!("Sawtooth PBFT Engine ({})", env!("CARGO_PKG_VERSION"));
 
     let (driver, _stop) = ZmqDriver::new();
@@ -72,17 +183,27 @@ fn main() {
     warn!("PBFT Node {} connecting to '{}'", &id, &endpoint);
     if dead >= 0 {
         warn!("    This node will be dead after {} seconds", dead);
-        let pbft_engine = crashing_node::engine::PbftEngine::new(id, dead);
+        #[cfg(feature = "metrics")]
+        let pbft_engine = crashing_node::engine::PbftEngine::new(
+            id, dead, config, informant, key_manager, metrics,
+        );
+        #[cfg(not(feature = "metrics"))]
+        let pbft_engine =
+            crashing_node::engine::PbftEngine::new(id, dead, config, informant, key_manager);
         driver.start(&endpoint, pbft_engine).unwrap_or_else(|err| {
             error!("{}", err);
             process::exit(1);
         });
     } else {
-        let pbft_engine = normal_node::engine::PbftEngine::new(id);
+        #[cfg(feature = "metrics")]
+        let pbft_engine =
+            normal_node::engine::PbftEngine::new(id, config, informant, key_manager, metrics);
+        #[cfg(not(feature = "metrics"))]
+        let pbft_engine =
+            normal_node::engine::PbftEngine::new(id, config, informant, key_manager);
         driver.start(&endpoint, pbft_engine).unwrap_or_else(|err| {
             error!("{}", err);
             process::exit(1);
         });
     }
-
 }
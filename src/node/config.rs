@@ -0,0 +1,52 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::time::Duration;
+
+use sawtooth_sdk::consensus::engine::PeerId;
+
+// Configuration for a PBFT node, assembled from defaults, a config file, and CLI overrides
+#[derive(Debug, Clone)]
+pub struct PbftConfig {
+    // Known peers in the network, including ourselves
+    pub peers: Vec<(PeerId, u64)>,
+
+    // How long to wait for a message from the primary before starting a view change
+    pub view_change_timeout: Duration,
+
+    // Upper bound on the exponentially-backed-off view change timeout (see
+    // `PbftState::consecutive_view_changes`)
+    pub view_change_max_timeout: Duration,
+
+    // Override for the maximum number of faulty nodes; defaults to (peers - 1) / 3
+    pub fault_tolerance: Option<u64>,
+
+    // Number of blocks between stable checkpoints
+    pub checkpoint_interval: u64,
+}
+
+impl PbftConfig {
+    pub fn default_with_peers(peers: Vec<(PeerId, u64)>) -> Self {
+        PbftConfig {
+            peers,
+            view_change_timeout: Duration::from_secs(10),
+            view_change_max_timeout: Duration::from_secs(160),
+            fault_tolerance: None,
+            checkpoint_interval: 100,
+        }
+    }
+}
@@ -0,0 +1,171 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+// Loading `PbftConfig` from a TOML file, for deployments where flags alone are too unwieldy
+// (a full peer table, non-default timeouts, etc). CLI flags are layered on top of whatever this
+// produces, so they always win.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use hex;
+
+use sawtooth_sdk::consensus::engine::PeerId;
+
+use node::config::PbftConfig;
+use node::error::PbftError;
+
+// Mirrors the TOML schema; every field is optional so a file only needs to override what it
+// cares about
+#[derive(Debug, Deserialize, Default)]
+struct TomlConfig {
+    peers: Option<HashMap<String, String>>,
+    fault_tolerance: Option<u64>,
+    view_change_timeout: Option<String>,
+    view_change_max_timeout: Option<String>,
+    checkpoint_interval: Option<u64>,
+}
+
+// Load a TOML config file and merge it onto `defaults`: every value the file sets replaces the
+// one in `defaults`, so CLI-flag overrides must be applied to the result afterwards. A peer table
+// in the file replaces `defaults.peers` entirely; `defaults.peers` is kept only if the file has
+// no peer table.
+pub fn load(path: &Path, defaults: PbftConfig) -> Result<PbftConfig, PbftError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| PbftError::Internal(format!("Could not read config file {:?}: {}", path, err)))?;
+
+    let toml_config: TomlConfig = toml::from_str(&contents)
+        .map_err(|err| PbftError::Internal(format!("Could not parse config file {:?}: {}", path, err)))?;
+
+    merge(toml_config, defaults)
+}
+
+fn merge(toml_config: TomlConfig, mut config: PbftConfig) -> Result<PbftConfig, PbftError> {
+    if let Some(peers) = toml_config.peers {
+        config.peers = parse_peer_table(peers)?;
+    }
+
+    if let Some(fault_tolerance) = toml_config.fault_tolerance {
+        config.fault_tolerance = Some(fault_tolerance);
+    }
+
+    if let Some(ref timeout) = toml_config.view_change_timeout {
+        config.view_change_timeout = to_duration(timeout)?;
+    }
+
+    if let Some(ref max_timeout) = toml_config.view_change_max_timeout {
+        config.view_change_max_timeout = to_duration(max_timeout)?;
+    }
+
+    if let Some(checkpoint_interval) = toml_config.checkpoint_interval {
+        config.checkpoint_interval = checkpoint_interval;
+    }
+
+    Ok(config)
+}
+
+// Parse a `node_id -> hex-encoded PeerId` table into the `(PeerId, node_id)` pairs `PbftConfig`
+// expects.
+fn parse_peer_table(peers: HashMap<String, String>) -> Result<Vec<(PeerId, u64)>, PbftError> {
+    peers
+        .into_iter()
+        .map(|(node_id, hex_peer_id)| {
+            let node_id = node_id.parse::<u64>().map_err(|err| {
+                PbftError::Internal(format!("Invalid node id {:?}: {}", node_id, err))
+            })?;
+            let peer_id_bytes = hex::decode(&hex_peer_id).map_err(|err| {
+                PbftError::Internal(format!("Invalid peer id {:?}: {}", hex_peer_id, err))
+            })?;
+            Ok((PeerId::from(peer_id_bytes), node_id))
+        })
+        .collect()
+}
+
+// Parse a human duration string like "20s", "500ms", "2m", or "1h" into a `Duration`
+pub fn to_duration(s: &str) -> Result<Duration, PbftError> {
+    let s = s.trim();
+
+    let (value, unit) = if let Some(stripped) = s.strip_suffix("ms") {
+        (stripped, "ms")
+    } else if let Some(stripped) = s.strip_suffix('s') {
+        (stripped, "s")
+    } else if let Some(stripped) = s.strip_suffix('m') {
+        (stripped, "m")
+    } else if let Some(stripped) = s.strip_suffix('h') {
+        (stripped, "h")
+    } else {
+        return Err(PbftError::Internal(format!(
+            "Duration {:?} is missing a unit suffix (ms, s, m, h)",
+            s
+        )));
+    };
+
+    let magnitude: u64 = value
+        .parse()
+        .map_err(|err| PbftError::Internal(format!("Invalid duration {:?}: {}", s, err)))?;
+
+    Ok(match unit {
+        "ms" => Duration::from_millis(magnitude),
+        "s" => Duration::from_secs(magnitude),
+        "m" => Duration::from_secs(magnitude * 60),
+        "h" => Duration::from_secs(magnitude * 60 * 60),
+        _ => unreachable!(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_duration_parses_known_units() {
+        assert_eq!(to_duration("20s").unwrap(), Duration::from_secs(20));
+        assert_eq!(to_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(to_duration("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(to_duration("1h").unwrap(), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn to_duration_rejects_missing_or_bad_unit() {
+        assert!(to_duration("20").is_err());
+        assert!(to_duration("20x").is_err());
+        assert!(to_duration("abcs").is_err());
+    }
+
+    #[test]
+    fn parse_peer_table_decodes_hex_peer_ids() {
+        let mut peers = HashMap::new();
+        peers.insert("0".to_string(), "00112233".to_string());
+        peers.insert("1".to_string(), "aabbccdd".to_string());
+
+        let mut parsed = parse_peer_table(peers).unwrap();
+        parsed.sort_by_key(|(_, node_id)| *node_id);
+
+        assert_eq!(parsed[0], (PeerId::from(vec![0x00, 0x11, 0x22, 0x33]), 0));
+        assert_eq!(parsed[1], (PeerId::from(vec![0xaa, 0xbb, 0xcc, 0xdd]), 1));
+    }
+
+    #[test]
+    fn parse_peer_table_rejects_invalid_hex() {
+        let mut peers = HashMap::new();
+        peers.insert("0".to_string(), "not-hex".to_string());
+
+        assert!(parse_peer_table(peers).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+// Signing and verification of PBFT consensus messages. Every outgoing PrePrepare / Prepare /
+// Commit / ViewChange / Checkpoint message is signed over a canonical digest of its core fields,
+// and a receiving node verifies that signature against the sender's public key before trusting
+// the message.
+
+use std::fs;
+use std::path::Path;
+
+use sawtooth_sdk::consensus::engine::BlockId;
+use sawtooth_sdk::signing;
+
+use node::error::PbftError;
+use node::message_type::PbftMessageType;
+
+fn signing_error(err: impl ToString) -> PbftError {
+    PbftError::SigningError(err.to_string())
+}
+
+// Holds this node's signing keypair, loaded from (or generated into) a data directory so the
+// node's identity survives restarts
+pub struct PbftKeyManager {
+    context: Box<dyn signing::Context>,
+    private_key: Box<dyn signing::PrivateKey>,
+    public_key: Box<dyn signing::PublicKey>,
+}
+
+impl PbftKeyManager {
+    // Load this node's keypair from `<data_dir>/keys/pbft-<id>.priv`, generating and persisting
+    // a new one if it doesn't already exist.
+    //
+    // `PbftState::verify_message` trusts the peer table's `PeerId` entries as signers' public
+    // keys, so a locally generated key is useless unless that table actually names it. If
+    // `expected_public_key_hex` is given (this node's own entry in the configured peer table),
+    // it must match the loaded/generated key or this fails loudly instead of producing
+    // signatures nobody else can ever verify. If no entry exists yet (first boot, before the
+    // operator has distributed this node's identity), the public key is logged so it can be
+    // copied into the peer table of every other node.
+    pub fn load_or_generate(
+        id: u64,
+        data_dir: &Path,
+        expected_public_key_hex: Option<&str>,
+    ) -> Result<Self, PbftError> {
+        let context = signing::create_context("secp256k1").map_err(signing_error)?;
+
+        let key_dir = data_dir.join("keys");
+        let key_path = key_dir.join(format!("pbft-{}.priv", id));
+
+        let private_key: Box<dyn signing::PrivateKey> = if key_path.exists() {
+            let hex_key = fs::read_to_string(&key_path).map_err(signing_error)?;
+            Box::new(
+                signing::secp256k1::Secp256k1PrivateKey::from_hex(hex_key.trim())
+                    .map_err(signing_error)?,
+            )
+        } else {
+            fs::create_dir_all(&key_dir).map_err(signing_error)?;
+            let new_key = context.new_random_private_key().map_err(signing_error)?;
+            fs::write(&key_path, new_key.as_hex()).map_err(signing_error)?;
+            new_key
+        };
+
+        let public_key = context
+            .get_public_key(&*private_key)
+            .map_err(signing_error)?;
+
+        match expected_public_key_hex {
+            Some(expected) if expected == public_key.as_hex() => {}
+            Some(expected) => {
+                return Err(PbftError::SigningError(format!(
+                    "Node {}'s keypair at {:?} (public key {}) doesn't match the peer table's \
+                     entry for this node ({}); no signature this node produces could ever verify. \
+                     Update the peer table, or remove the keypair to generate a fresh one and \
+                     republish its public key.",
+                    id,
+                    key_path,
+                    public_key.as_hex(),
+                    expected
+                )));
+            }
+            None => {
+                warn!(
+                    "Node {} has no entry in the configured peer table yet; its public key is \
+                     {} -- add it to every other node's peer table before they can accept this \
+                     node's messages",
+                    id,
+                    public_key.as_hex()
+                );
+            }
+        }
+
+        Ok(PbftKeyManager {
+            context,
+            private_key,
+            public_key,
+        })
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        self.public_key.as_hex()
+    }
+
+    // Sign the canonical digest of a consensus message, returning the raw signature bytes to
+    // attach to the outgoing protobuf
+    pub fn sign(&self, digest: &[u8]) -> Result<Vec<u8>, PbftError> {
+        let hex_sig = self
+            .context
+            .sign(digest, &*self.private_key)
+            .map_err(signing_error)?;
+        hex::decode(hex_sig).map_err(signing_error)
+    }
+}
+
+// Verify `signature` over `digest` against the hex-encoded public key `signer_public_key`
+pub fn verify(signer_public_key: &str, digest: &[u8], signature: &[u8]) -> Result<(), PbftError> {
+    let context = signing::create_context("secp256k1").map_err(signing_error)?;
+    let key =
+        signing::secp256k1::Secp256k1PublicKey::from_hex(signer_public_key).map_err(signing_error)?;
+
+    let verified = context
+        .verify(&hex::encode(signature), digest, &key)
+        .map_err(signing_error)?;
+
+    if verified {
+        Ok(())
+    } else {
+        Err(PbftError::SigningError(
+            "signature did not verify".into(),
+        ))
+    }
+}
+
+// Build the canonical digest that is signed/verified for a consensus message: its type, view,
+// sequence number, block id, and the id of the node that claims to be sending it
+pub fn message_digest(
+    msg_type: &PbftMessageType,
+    view: u64,
+    seq_num: u64,
+    block_id: &BlockId,
+    sender_id: u64,
+) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(String::from(msg_type).as_bytes());
+    bytes.extend_from_slice(&view.to_be_bytes());
+    bytes.extend_from_slice(&seq_num.to_be_bytes());
+    bytes.extend_from_slice(block_id.as_slice());
+    bytes.extend_from_slice(&sender_id.to_be_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keypair() -> (
+        Box<dyn signing::Context>,
+        Box<dyn signing::PrivateKey>,
+        Box<dyn signing::PublicKey>,
+    ) {
+        let context = signing::create_context("secp256k1").unwrap();
+        let private_key = context.new_random_private_key().unwrap();
+        let public_key = context.get_public_key(&*private_key).unwrap();
+        (context, private_key, public_key)
+    }
+
+    #[test]
+    fn message_digest_differs_per_field() {
+        let block_id = BlockId::from(vec![0xaa; 32]);
+        let base = message_digest(&PbftMessageType::Prepare, 1, 1, &block_id, 0);
+
+        assert_ne!(
+            base,
+            message_digest(&PbftMessageType::Commit, 1, 1, &block_id, 0),
+            "msg_type should affect the digest"
+        );
+        assert_ne!(
+            base,
+            message_digest(&PbftMessageType::Prepare, 2, 1, &block_id, 0),
+            "view should affect the digest"
+        );
+        assert_ne!(
+            base,
+            message_digest(&PbftMessageType::Prepare, 1, 2, &block_id, 0),
+            "seq_num should affect the digest"
+        );
+        assert_ne!(
+            base,
+            message_digest(&PbftMessageType::Prepare, 1, 1, &block_id, 1),
+            "sender_id should affect the digest"
+        );
+        assert_ne!(
+            base,
+            message_digest(
+                &PbftMessageType::Prepare,
+                1,
+                1,
+                &BlockId::from(vec![0xbb; 32]),
+                0
+            ),
+            "block_id should affect the digest"
+        );
+    }
+
+    #[test]
+    fn sign_then_verify_round_trips() {
+        let (context, private_key, public_key) = keypair();
+        let digest = message_digest(
+            &PbftMessageType::Prepare,
+            1,
+            1,
+            &BlockId::from(vec![0xaa; 32]),
+            0,
+        );
+
+        let hex_sig = context.sign(&digest, &*private_key).unwrap();
+        let signature = hex::decode(hex_sig).unwrap();
+
+        verify(&public_key.as_hex(), &digest, &signature).expect("signature should verify");
+    }
+
+    #[test]
+    fn verify_rejects_wrong_signer() {
+        let (context, private_key, _) = keypair();
+        let (_, _, other_public_key) = keypair();
+        let digest = message_digest(
+            &PbftMessageType::Prepare,
+            1,
+            1,
+            &BlockId::from(vec![0xaa; 32]),
+            0,
+        );
+
+        let hex_sig = context.sign(&digest, &*private_key).unwrap();
+        let signature = hex::decode(hex_sig).unwrap();
+
+        assert!(verify(&other_public_key.as_hex(), &digest, &signature).is_err());
+    }
+
+    #[test]
+    fn verify_rejects_tampered_digest() {
+        let (context, private_key, public_key) = keypair();
+        let digest = message_digest(
+            &PbftMessageType::Prepare,
+            1,
+            1,
+            &BlockId::from(vec![0xaa; 32]),
+            0,
+        );
+
+        let hex_sig = context.sign(&digest, &*private_key).unwrap();
+        let signature = hex::decode(hex_sig).unwrap();
+
+        let mut tampered_digest = digest.clone();
+        tampered_digest[0] ^= 0xff;
+
+        assert!(verify(&public_key.as_hex(), &tampered_digest, &signature).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::fmt;
+
+// Errors that can occur during PBFT consensus processing
+#[derive(Debug)]
+pub enum PbftError {
+    // A message referenced a node id that isn't in the network's peer table
+    NodeNotFound,
+
+    // This node's signing key couldn't be loaded, generated or used, or a message's signature
+    // didn't verify against the key of its claimed sender
+    SigningError(String),
+
+    // Catch-all for anything else that went wrong
+    Internal(String),
+}
+
+impl fmt::Display for PbftError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PbftError::NodeNotFound => write!(f, "Node not found in network"),
+            PbftError::SigningError(msg) => write!(f, "Signing error: {}", msg),
+            PbftError::Internal(msg) => write!(f, "Internal error: {}", msg),
+        }
+    }
+}
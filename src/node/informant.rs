@@ -0,0 +1,50 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+// Periodically logs a one-line consensus status, so operators can spot a stuck primary or a
+// view-change storm without turning on trace logging. `PbftState` already has a `Display` impl
+// covering phase/mode/view/seq_num/working-block; this just emits it on a timer instead of only
+// on phase transitions.
+
+use std::time::{Duration, Instant};
+
+use node::state::PbftState;
+
+pub struct Informant {
+    interval: Duration,
+    last_emit: Instant,
+}
+
+impl Informant {
+    pub fn new(interval: Duration) -> Self {
+        Informant {
+            interval,
+            last_emit: Instant::now(),
+        }
+    }
+
+    // Emit the status line if `interval` has elapsed since the last one; call this from the
+    // engine's main loop.
+    pub fn tick(&mut self, state: &PbftState) {
+        if self.last_emit.elapsed() < self.interval {
+            return;
+        }
+
+        info!("{}", state);
+        self.last_emit = Instant::now();
+    }
+}
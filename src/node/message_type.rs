@@ -30,6 +30,11 @@ pub enum PbftMessageType {
     Checkpoint,
     ViewChange,
 
+    // Membership-change proposals; ordered through normal consensus like any other request, but
+    // only take effect at a stable checkpoint boundary
+    AddNode,
+    RemoveNode,
+
     Unset,
 }
 
@@ -42,6 +47,8 @@ impl fmt::Display for PbftMessageType {
             PbftMessageType::BlockNew => "BN",
             PbftMessageType::Checkpoint => "CP",
             PbftMessageType::ViewChange => "VC",
+            PbftMessageType::AddNode => "AN",
+            PbftMessageType::RemoveNode => "RN",
             PbftMessageType::Unset => "Un",
         };
         write!(f, "{}", txt)
@@ -68,6 +75,8 @@ impl<'a> From<&'a str> for PbftMessageType {
             "BlockNew" => PbftMessageType::BlockNew,
             "ViewChange" => PbftMessageType::ViewChange,
             "Checkpoint" => PbftMessageType::Checkpoint,
+            "AddNode" => PbftMessageType::AddNode,
+            "RemoveNode" => PbftMessageType::RemoveNode,
             _ => {
                 warn!("Unhandled PBFT message type: {}", s);
                 PbftMessageType::Unset
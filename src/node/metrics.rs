@@ -0,0 +1,168 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+// Prometheus metrics for consensus observability, gated behind the `metrics` cargo feature so
+// the core engine carries no hard dependency on an HTTP stack. A binary built without the feature
+// never links this code in; with it, the HTTP server only starts when `--metrics <addr>` is given.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+use prometheus::{Encoder, GaugeVec, IntCounter, IntGauge, Opts, Registry, TextEncoder};
+use tiny_http::{Response, Server};
+
+use node::error::PbftError;
+use node::state::{PbftPhase, PbftState};
+
+pub struct PbftMetrics {
+    registry: Registry,
+    view: IntGauge,
+    seq_num: IntGauge,
+    is_primary: IntGauge,
+    reachable_peers: IntGauge,
+    view_changes_total: IntCounter,
+    checkpoints_total: IntCounter,
+    // One time series per `PbftPhase`, labeled by phase name, so operators can see where time is
+    // actually going instead of a single scalar for whatever phase happened to be current at
+    // scrape time
+    phase_seconds: GaugeVec,
+}
+
+impl PbftMetrics {
+    pub fn new() -> Result<Self, PbftError> {
+        let registry = Registry::new();
+
+        let view = IntGauge::with_opts(Opts::new("pbft_view", "Current PBFT view")).unwrap();
+        let seq_num =
+            IntGauge::with_opts(Opts::new("pbft_seq_num", "Current PBFT sequence number")).unwrap();
+        let is_primary =
+            IntGauge::with_opts(Opts::new("pbft_is_primary", "1 if this node is primary")).unwrap();
+        let reachable_peers = IntGauge::with_opts(Opts::new(
+            "pbft_reachable_peers",
+            "Number of peers in the current validator set",
+        ))
+        .unwrap();
+        let view_changes_total = IntCounter::with_opts(Opts::new(
+            "pbft_view_changes_total",
+            "Total number of view changes initiated",
+        ))
+        .unwrap();
+        let checkpoints_total = IntCounter::with_opts(Opts::new(
+            "pbft_checkpoints_total",
+            "Total number of stable checkpoints reached",
+        ))
+        .unwrap();
+        let phase_seconds = GaugeVec::new(
+            Opts::new(
+                "pbft_phase_seconds",
+                "Cumulative seconds spent in each PbftPhase",
+            ),
+            &["phase"],
+        )
+        .unwrap();
+
+        registry
+            .register(Box::new(view.clone()))
+            .map_err(|err| PbftError::Internal(err.to_string()))?;
+        registry
+            .register(Box::new(seq_num.clone()))
+            .map_err(|err| PbftError::Internal(err.to_string()))?;
+        registry
+            .register(Box::new(is_primary.clone()))
+            .map_err(|err| PbftError::Internal(err.to_string()))?;
+        registry
+            .register(Box::new(reachable_peers.clone()))
+            .map_err(|err| PbftError::Internal(err.to_string()))?;
+        registry
+            .register(Box::new(view_changes_total.clone()))
+            .map_err(|err| PbftError::Internal(err.to_string()))?;
+        registry
+            .register(Box::new(checkpoints_total.clone()))
+            .map_err(|err| PbftError::Internal(err.to_string()))?;
+        registry
+            .register(Box::new(phase_seconds.clone()))
+            .map_err(|err| PbftError::Internal(err.to_string()))?;
+
+        Ok(PbftMetrics {
+            registry,
+            view,
+            seq_num,
+            is_primary,
+            reachable_peers,
+            view_changes_total,
+            checkpoints_total,
+            phase_seconds,
+        })
+    }
+
+    // Refresh all gauges/counters from the current state. Counters can't be set directly, so
+    // each one is incremented by however far it lags the state's lifetime total.
+    pub fn observe(&self, state: &PbftState) {
+        self.view.set(state.view as i64);
+        self.seq_num.set(state.seq_num as i64);
+        self.is_primary.set(if state.is_primary() { 1 } else { 0 });
+        self.reachable_peers.set(state.peer_count() as i64);
+
+        let view_changes_delta = state
+            .view_change_count
+            .saturating_sub(self.view_changes_total.get());
+        if view_changes_delta > 0 {
+            self.view_changes_total.inc_by(view_changes_delta);
+        }
+        let checkpoints_delta = state
+            .checkpoint_count
+            .saturating_sub(self.checkpoints_total.get());
+        if checkpoints_delta > 0 {
+            self.checkpoints_total.inc_by(checkpoints_delta);
+        }
+
+        let durations = state.phase_durations();
+        for phase in PbftPhase::all() {
+            let seconds = durations.get(phase).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+            self.phase_seconds
+                .with_label_values(&[phase.as_str()])
+                .set(seconds);
+        }
+    }
+
+    fn render(&self) -> Vec<u8> {
+        let metric_families = self.registry.gather();
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        encoder.encode(&metric_families, &mut buffer).unwrap();
+        buffer
+    }
+}
+
+// Spawn a background thread serving `/metrics` in Prometheus text format at `addr`.
+pub fn serve(metrics: Arc<PbftMetrics>, addr: SocketAddr) -> Result<(), PbftError> {
+    let server = Server::http(addr).map_err(|err| PbftError::Internal(err.to_string()))?;
+
+    thread::Builder::new()
+        .name("pbft-metrics".into())
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                let body = metrics.render();
+                let response = Response::from_data(body);
+                let _ = request.respond(response);
+            }
+        })
+        .map_err(|err| PbftError::Internal(err.to_string()))?;
+
+    Ok(())
+}
@@ -0,0 +1,331 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+// Durable checkpointing of `PbftState` so that a crashed or restarted node resumes from where it
+// left off instead of being treated as freshly joined. A `Storage` implementation is responsible
+// only for getting bytes on and off of some medium; `PbftSnapshot` owns the (de)serialization of
+// the fields that matter.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use hex;
+use protobuf::Message;
+use serde_json;
+
+use sawtooth_sdk::consensus::engine::BlockId;
+
+use protos::pbft_message::PbftBlock;
+
+use node::error::PbftError;
+use node::state::{PbftMode, PbftPhase, WorkingBlockOption};
+
+// The subset of `PbftState` that needs to survive a restart: enough to resume consensus from the
+// persisted phase without replaying work that already completed
+#[derive(Debug, Clone)]
+pub struct PbftSnapshot {
+    pub seq_num: u64,
+    pub view: u64,
+    pub phase: PbftPhase,
+    pub mode: PbftMode,
+    pub pre_checkpoint_mode: PbftMode,
+    pub working_block: WorkingBlockOption,
+
+    // Already-serialized Prepare/Commit certificates backing `working_block`, handed to us
+    // opaquely by the message log so this module doesn't need to know its internal layout
+    pub certificates: Vec<Vec<u8>>,
+
+    // Sequence number of the latest stable checkpoint; on load, message-log entries below this
+    // are discarded rather than replayed
+    pub stable_checkpoint_seq_num: u64,
+}
+
+// Anything that can durably hold a `PbftSnapshot` between process restarts, and prune it once a
+// later checkpoint makes the older data unnecessary
+pub trait Storage {
+    fn save(&self, snapshot: &PbftSnapshot) -> Result<(), PbftError>;
+    fn load(&self) -> Result<Option<PbftSnapshot>, PbftError>;
+
+    // Forget everything at or below `stable_checkpoint_seq_num`; a no-op for stores that don't
+    // retain history beyond the latest snapshot
+    fn prune(&self, stable_checkpoint_seq_num: u64) -> Result<(), PbftError>;
+}
+
+// Keeps the latest snapshot in memory only; used in tests so consensus logic can be exercised
+// without touching the filesystem
+#[derive(Default)]
+pub struct MemoryStorage {
+    snapshot: ::std::sync::Mutex<Option<PbftSnapshot>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn save(&self, snapshot: &PbftSnapshot) -> Result<(), PbftError> {
+        *self.snapshot.lock().unwrap() = Some(snapshot.clone());
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Option<PbftSnapshot>, PbftError> {
+        Ok(self.snapshot.lock().unwrap().clone())
+    }
+
+    fn prune(&self, _stable_checkpoint_seq_num: u64) -> Result<(), PbftError> {
+        Ok(())
+    }
+}
+
+// Persists the snapshot as a single JSON file in a configurable data directory. Each save
+// overwrites the previous one, so pruning is implicit; `prune` only records the watermark for
+// diagnostics.
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(data_dir: &Path, node_id: u64) -> Result<Self, PbftError> {
+        fs::create_dir_all(data_dir).map_err(|err| PbftError::Internal(err.to_string()))?;
+        Ok(FileStorage {
+            path: data_dir.join(format!("pbft-{}.state", node_id)),
+        })
+    }
+}
+
+impl Storage for FileStorage {
+    fn save(&self, snapshot: &PbftSnapshot) -> Result<(), PbftError> {
+        let json = snapshot_to_json(snapshot);
+        let tmp_path = self.path.with_extension("state.tmp");
+        fs::write(&tmp_path, json.to_string()).map_err(|err| PbftError::Internal(err.to_string()))?;
+        fs::rename(&tmp_path, &self.path).map_err(|err| PbftError::Internal(err.to_string()))
+    }
+
+    fn load(&self) -> Result<Option<PbftSnapshot>, PbftError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&self.path).map_err(|err| PbftError::Internal(err.to_string()))?;
+        let json: serde_json::Value =
+            serde_json::from_str(&contents).map_err(|err| PbftError::Internal(err.to_string()))?;
+        Ok(Some(snapshot_from_json(&json)?))
+    }
+
+    fn prune(&self, stable_checkpoint_seq_num: u64) -> Result<(), PbftError> {
+        debug!(
+            "Pruned persisted state below stable checkpoint {}",
+            stable_checkpoint_seq_num
+        );
+        Ok(())
+    }
+}
+
+fn phase_to_str(phase: &PbftPhase) -> &'static str {
+    match phase {
+        PbftPhase::NotStarted => "NotStarted",
+        PbftPhase::PrePreparing => "PrePreparing",
+        PbftPhase::Preparing => "Preparing",
+        PbftPhase::Checking => "Checking",
+        PbftPhase::Committing => "Committing",
+        PbftPhase::Finished => "Finished",
+    }
+}
+
+fn phase_from_str(s: &str) -> Result<PbftPhase, PbftError> {
+    match s {
+        "NotStarted" => Ok(PbftPhase::NotStarted),
+        "PrePreparing" => Ok(PbftPhase::PrePreparing),
+        "Preparing" => Ok(PbftPhase::Preparing),
+        "Checking" => Ok(PbftPhase::Checking),
+        "Committing" => Ok(PbftPhase::Committing),
+        "Finished" => Ok(PbftPhase::Finished),
+        other => Err(PbftError::Internal(format!("Unknown persisted phase: {}", other))),
+    }
+}
+
+fn mode_to_str(mode: &PbftMode) -> &'static str {
+    match mode {
+        PbftMode::Normal => "Normal",
+        PbftMode::ViewChanging => "ViewChanging",
+        PbftMode::Checkpointing => "Checkpointing",
+    }
+}
+
+fn mode_from_str(s: &str) -> Result<PbftMode, PbftError> {
+    match s {
+        "Normal" => Ok(PbftMode::Normal),
+        "ViewChanging" => Ok(PbftMode::ViewChanging),
+        "Checkpointing" => Ok(PbftMode::Checkpointing),
+        other => Err(PbftError::Internal(format!("Unknown persisted mode: {}", other))),
+    }
+}
+
+fn snapshot_to_json(snapshot: &PbftSnapshot) -> serde_json::Value {
+    let working_block = match &snapshot.working_block {
+        WorkingBlockOption::NoWorkingBlock => serde_json::Value::Null,
+        WorkingBlockOption::TentativeWorkingBlock(block_id) => {
+            json!({ "tentative_block_id": hex::encode(block_id) })
+        }
+        WorkingBlockOption::WorkingBlock(block) => {
+            json!({ "block": hex::encode(block.write_to_bytes().unwrap_or_default()) })
+        }
+    };
+
+    json!({
+        "seq_num": snapshot.seq_num,
+        "view": snapshot.view,
+        "phase": phase_to_str(&snapshot.phase),
+        "mode": mode_to_str(&snapshot.mode),
+        "pre_checkpoint_mode": mode_to_str(&snapshot.pre_checkpoint_mode),
+        "working_block": working_block,
+        "certificates": snapshot.certificates.iter().map(hex::encode).collect::<Vec<_>>(),
+        "stable_checkpoint_seq_num": snapshot.stable_checkpoint_seq_num,
+    })
+}
+
+fn snapshot_from_json(json: &serde_json::Value) -> Result<PbftSnapshot, PbftError> {
+    let err = || PbftError::Internal("Malformed persisted state".into());
+
+    let working_block = match json.get("working_block") {
+        None | Some(serde_json::Value::Null) => WorkingBlockOption::NoWorkingBlock,
+        Some(value) => {
+            if let Some(hex_id) = value.get("tentative_block_id").and_then(|v| v.as_str()) {
+                let bytes = hex::decode(hex_id).map_err(|_| err())?;
+                WorkingBlockOption::TentativeWorkingBlock(BlockId::from(bytes))
+            } else if let Some(hex_block) = value.get("block").and_then(|v| v.as_str()) {
+                let bytes = hex::decode(hex_block).map_err(|_| err())?;
+                let block: PbftBlock = protobuf::parse_from_bytes(&bytes)
+                    .map_err(|parse_err| PbftError::Internal(parse_err.to_string()))?;
+                WorkingBlockOption::WorkingBlock(block)
+            } else {
+                return Err(err());
+            }
+        }
+    };
+
+    let certificates = json
+        .get("certificates")
+        .and_then(|v| v.as_array())
+        .map(|certs| {
+            certs
+                .iter()
+                .filter_map(|c| c.as_str())
+                .filter_map(|c| hex::decode(c).ok())
+                .collect()
+        })
+        .unwrap_or_else(Vec::new);
+
+    Ok(PbftSnapshot {
+        seq_num: json.get("seq_num").and_then(|v| v.as_u64()).ok_or_else(err)?,
+        view: json.get("view").and_then(|v| v.as_u64()).ok_or_else(err)?,
+        phase: phase_from_str(json.get("phase").and_then(|v| v.as_str()).ok_or_else(err)?)?,
+        mode: mode_from_str(json.get("mode").and_then(|v| v.as_str()).ok_or_else(err)?)?,
+        pre_checkpoint_mode: mode_from_str(
+            json.get("pre_checkpoint_mode").and_then(|v| v.as_str()).ok_or_else(err)?,
+        )?,
+        working_block,
+        certificates,
+        stable_checkpoint_seq_num: json
+            .get("stable_checkpoint_seq_num")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(err)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot_with(working_block: WorkingBlockOption) -> PbftSnapshot {
+        PbftSnapshot {
+            seq_num: 7,
+            view: 2,
+            phase: PbftPhase::Committing,
+            mode: PbftMode::ViewChanging,
+            pre_checkpoint_mode: PbftMode::Normal,
+            working_block,
+            certificates: vec![vec![0xde, 0xad], vec![0xbe, 0xef]],
+            stable_checkpoint_seq_num: 3,
+        }
+    }
+
+    fn assert_round_trips(snapshot: PbftSnapshot) {
+        let json = snapshot_to_json(&snapshot);
+        let restored = snapshot_from_json(&json).expect("round-trip should parse");
+
+        assert_eq!(restored.seq_num, snapshot.seq_num);
+        assert_eq!(restored.view, snapshot.view);
+        assert_eq!(restored.phase, snapshot.phase);
+        assert_eq!(restored.mode, snapshot.mode);
+        assert_eq!(restored.pre_checkpoint_mode, snapshot.pre_checkpoint_mode);
+        assert_eq!(restored.working_block, snapshot.working_block);
+        assert_eq!(restored.certificates, snapshot.certificates);
+        assert_eq!(
+            restored.stable_checkpoint_seq_num,
+            snapshot.stable_checkpoint_seq_num
+        );
+    }
+
+    #[test]
+    fn round_trips_with_no_working_block() {
+        assert_round_trips(snapshot_with(WorkingBlockOption::NoWorkingBlock));
+    }
+
+    #[test]
+    fn round_trips_with_tentative_working_block() {
+        assert_round_trips(snapshot_with(WorkingBlockOption::TentativeWorkingBlock(
+            BlockId::from(vec![0x01, 0x02, 0x03]),
+        )));
+    }
+
+    #[test]
+    fn phase_and_mode_round_trip_every_variant() {
+        for phase in &[
+            PbftPhase::NotStarted,
+            PbftPhase::PrePreparing,
+            PbftPhase::Preparing,
+            PbftPhase::Checking,
+            PbftPhase::Committing,
+            PbftPhase::Finished,
+        ] {
+            assert_eq!(&phase_from_str(phase_to_str(phase)).unwrap(), phase);
+        }
+
+        for mode in &[
+            PbftMode::Normal,
+            PbftMode::ViewChanging,
+            PbftMode::Checkpointing,
+        ] {
+            assert_eq!(&mode_from_str(mode_to_str(mode)).unwrap(), mode);
+        }
+    }
+
+    #[test]
+    fn snapshot_from_json_rejects_missing_fields() {
+        let json = json!({ "seq_num": 1 });
+        assert!(snapshot_from_json(&json).is_err());
+    }
+
+    #[test]
+    fn snapshot_from_json_rejects_unknown_phase() {
+        let mut json = snapshot_to_json(&snapshot_with(WorkingBlockOption::NoWorkingBlock));
+        json["phase"] = json!("NotAPhase");
+        assert!(snapshot_from_json(&json).is_err());
+    }
+}
@@ -17,6 +17,7 @@
 
 use std::collections::HashMap;
 use std::fmt;
+use std::time::{Duration, Instant};
 
 use hex;
 
@@ -25,7 +26,9 @@ use sawtooth_sdk::consensus::engine::{PeerId, BlockId};
 use protos::pbft_message::PbftBlock;
 
 use node::config::PbftConfig;
+use node::crypto;
 use node::message_type::PbftMessageType;
+use node::persistence::PbftSnapshot;
 use node::timing::Timeout;
 use node::error::PbftError;
 
@@ -38,7 +41,7 @@ enum PbftNodeRole {
 }
 
 // Stages of the PBFT algorithm
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Hash, Clone)]
 pub enum PbftPhase {
     NotStarted,
     PrePreparing,
@@ -48,6 +51,31 @@ pub enum PbftPhase {
     Finished,
 }
 
+impl PbftPhase {
+    // All phases, for callers (e.g. Prometheus metrics) that need to report a value for each one
+    pub fn all() -> &'static [PbftPhase] {
+        &[
+            PbftPhase::NotStarted,
+            PbftPhase::PrePreparing,
+            PbftPhase::Preparing,
+            PbftPhase::Checking,
+            PbftPhase::Committing,
+            PbftPhase::Finished,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PbftPhase::NotStarted => "NotStarted",
+            PbftPhase::PrePreparing => "PrePreparing",
+            PbftPhase::Preparing => "Preparing",
+            PbftPhase::Checking => "Checking",
+            PbftPhase::Committing => "Committing",
+            PbftPhase::Finished => "Finished",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum PbftMode {
     Normal,
@@ -150,12 +178,38 @@ pub struct PbftState {
     // initiate a view change.
     pub timeout: Timeout,
 
+    // Base duration for `timeout`, and the ceiling its exponential backoff is clamped to; kept
+    // around so the timeout can be recomputed as `consecutive_view_changes` changes
+    base_view_change_timeout: Duration,
+    max_view_change_timeout: Duration,
+
+    // Number of view changes in a row that have been initiated without the node successfully
+    // returning to `PbftMode::Normal`. Used to back off `timeout` exponentially so an unstable
+    // network doesn't churn through views faster than it can agree on a new primary.
+    pub consecutive_view_changes: u64,
+
+    // Lifetime counters, surfaced to operators via the status informant and (optionally)
+    // Prometheus metrics
+    pub view_change_count: u64,
+    pub checkpoint_count: u64,
+
+    // Cumulative time spent in each phase, not counting however long we've currently been in
+    // `phase` (see `phase_durations`); updated whenever `switch_phase` moves us out of a phase
+    phase_totals: HashMap<PbftPhase, Duration>,
+    phase_entered_at: Instant,
+
     // The current block we're working on
     pub working_block: WorkingBlockOption,
 }
 
 impl PbftState {
-    pub fn new(id: u64, config: &PbftConfig) -> Self {
+    pub fn new(id: u64, config: &PbftConfig) -> Result<Self, PbftError> {
+        if config.peers.is_empty() {
+            return Err(PbftError::Internal(
+                "PbftConfig has no peers; refusing to start with an empty network".into(),
+            ));
+        }
+
         let peer_id_map: HashMap<u64, PeerId> = config
             .peers
             .clone()
@@ -163,13 +217,16 @@ impl PbftState {
             .map(|(peer_id, node_id)| (node_id, peer_id))
             .collect();
 
-        // Maximum number of faulty nodes in this network
-        let f = ((peer_id_map.len() - 1) / 3) as u64;
+        // Maximum number of faulty nodes in this network: an explicit override (from the config
+        // file or --fault-tolerance) takes precedence, otherwise fall back to the standard PBFT bound
+        let f = config
+            .fault_tolerance
+            .unwrap_or_else(|| ((peer_id_map.len() - 1) / 3) as u64);
         if f == 0 {
             warn!("This network does not contain enough nodes to be fault tolerant");
         }
 
-        PbftState {
+        Ok(PbftState {
             id: id,
             seq_num: 0, // Default to unknown
             view: 0,    // Node ID 0 is default primary
@@ -184,8 +241,15 @@ impl PbftState {
             f: f,
             network_node_ids: peer_id_map,
             timeout: Timeout::new(config.view_change_timeout.clone()),
+            base_view_change_timeout: config.view_change_timeout,
+            max_view_change_timeout: config.view_change_max_timeout,
+            consecutive_view_changes: 0,
+            view_change_count: 0,
+            checkpoint_count: 0,
+            phase_totals: HashMap::new(),
+            phase_entered_at: Instant::now(),
             working_block: WorkingBlockOption::NoWorkingBlock,
-        }
+        })
     }
 
     // Checks to see what type of message we're expecting or sending, based on what phase we're in
@@ -200,6 +264,9 @@ impl PbftState {
     }
 
     // Obtain the node ID from a serialized PeerId
+    //
+    // This only tells us who *claims* to have sent a message, not who actually signed it; prefer
+    // `verify_message` for any message that needs to be trusted.
     pub fn get_node_id_from_bytes(&self, peer_id: &[u8]) -> Result<u64, PbftError> {
         let deser_id = PeerId::from(peer_id.to_vec());
 
@@ -216,10 +283,39 @@ impl PbftState {
         }
     }
 
+    // Authenticate a consensus message: check that `signature` is a valid signature, by the node
+    // that `claimed_sender_id` names, over the canonical digest of
+    // `(msg_type, view, seq_num, block_id, claimed_sender_id)`. Returns the authenticated sender
+    // node ID on success, so callers don't need a separate trust-but-verify step.
+    pub fn verify_message(
+        &self,
+        msg_type: &PbftMessageType,
+        view: u64,
+        seq_num: u64,
+        block_id: &BlockId,
+        claimed_sender_id: u64,
+        signature: &[u8],
+    ) -> Result<u64, PbftError> {
+        let signer_peer_id = self.network_node_ids
+            .get(&claimed_sender_id)
+            .ok_or(PbftError::NodeNotFound)?;
+
+        let digest = crypto::message_digest(msg_type, view, seq_num, block_id, claimed_sender_id);
+
+        crypto::verify(&hex::encode(signer_peer_id.as_slice()), &digest, signature)?;
+
+        Ok(claimed_sender_id)
+    }
+
     pub fn get_own_peer_id(&self) -> PeerId {
         self.network_node_ids[&self.id].clone()
     }
 
+    // Number of nodes in the current validator set, including ourselves
+    pub fn peer_count(&self) -> usize {
+        self.network_node_ids.len()
+    }
+
     pub fn get_primary_peer_id(&self) -> PeerId {
         let primary_node_id = self.view % (self.network_node_ids.len() as u64);
         self.network_node_ids[&primary_node_id].clone()
@@ -240,6 +336,111 @@ impl PbftState {
         self.role = PbftNodeRole::Secondary;
     }
 
+    // Record that a view change is being initiated without having reached `PbftMode::Normal`,
+    // and back off `timeout` exponentially (base * 2^consecutive_view_changes, clamped to
+    // `max_view_change_timeout`) so a partitioned or slow network eventually stabilizes on a
+    // primary instead of oscillating through views faster than it can agree on one.
+    pub fn back_off_view_change_timeout(&mut self) {
+        self.consecutive_view_changes += 1;
+        self.view_change_count += 1;
+
+        let multiplier = 1u32
+            .checked_shl(self.consecutive_view_changes.min(31) as u32)
+            .unwrap_or(u32::max_value());
+        let backed_off = self.base_view_change_timeout
+            .checked_mul(multiplier)
+            .unwrap_or(self.max_view_change_timeout);
+        let next_timeout = backed_off.min(self.max_view_change_timeout);
+
+        self.timeout = Timeout::new(next_timeout);
+
+        warn!(
+            "{}: View change {} in a row; backing off timeout to {:?}",
+            self, self.consecutive_view_changes, next_timeout
+        );
+    }
+
+    // Reset the view change backoff; called whenever the node successfully returns to
+    // `PbftMode::Normal` and completes a block.
+    pub fn reset_view_change_backoff(&mut self) {
+        self.consecutive_view_changes = 0;
+        self.timeout = Timeout::new(self.base_view_change_timeout);
+    }
+
+    // Record that a stable checkpoint was reached; surfaced via the status informant and
+    // Prometheus metrics.
+    pub fn record_checkpoint(&mut self) {
+        self.checkpoint_count += 1;
+    }
+
+    // Apply a membership-change proposal (`PbftMessageType::AddNode` or `RemoveNode`) that has
+    // been ordered through normal consensus. `target_checkpoint_seq_num` is the stable checkpoint
+    // the proposal was agreed to take effect at; this is rejected unless `self.seq_num` has
+    // actually reached it, so the caller can't apply the change early and have honest nodes
+    // diverge on when the validator set changed. Recomputes `f` and this node's primary/secondary
+    // role against the new set size. A newly added node should be bootstrapped from the
+    // checkpoint this change lands on; a removed node downgrades (it can no longer be primary)
+    // and should be drained by the caller.
+    pub fn apply_membership_change(
+        &mut self,
+        msg_type: &PbftMessageType,
+        node_id: u64,
+        peer_id: Option<PeerId>,
+        target_checkpoint_seq_num: u64,
+    ) -> Result<(), PbftError> {
+        if self.seq_num != target_checkpoint_seq_num {
+            return Err(PbftError::Internal(format!(
+                "Membership change targets checkpoint {}, but this node is at seq_num {}; \
+                 refusing to apply off of a checkpoint boundary",
+                target_checkpoint_seq_num, self.seq_num
+            )));
+        }
+
+        match msg_type {
+            PbftMessageType::AddNode => {
+                let peer_id = peer_id.ok_or_else(|| {
+                    PbftError::Internal("AddNode proposal is missing a PeerId".into())
+                })?;
+                if self.network_node_ids.contains_key(&node_id) {
+                    return Err(PbftError::Internal(format!(
+                        "Node {} is already in the network; rejecting AddNode proposal that \
+                         would silently replace its PeerId",
+                        node_id
+                    )));
+                }
+                self.network_node_ids.insert(node_id, peer_id);
+            }
+            PbftMessageType::RemoveNode => {
+                let new_len = self.network_node_ids.len().saturating_sub(1) as u64;
+                if new_len < 3 * self.f + 1 {
+                    return Err(PbftError::Internal(format!(
+                        "Removing node {} would shrink the network to {} nodes, below the 3f+1 \
+                         safety threshold for f = {}",
+                        node_id, new_len, self.f
+                    )));
+                }
+                self.network_node_ids.remove(&node_id);
+            }
+            _ => return Err(PbftError::Internal("Not a membership-change message".into())),
+        }
+
+        self.f = ((self.network_node_ids.len() - 1) / 3) as u64;
+        self.re_evaluate_role();
+
+        Ok(())
+    }
+
+    // Recompute whether this node is primary or secondary under the current view and network
+    // size; used after the validator set changes.
+    fn re_evaluate_role(&mut self) {
+        let primary_node_id = self.view % (self.network_node_ids.len() as u64);
+        if self.id == primary_node_id && self.network_node_ids.contains_key(&self.id) {
+            self.upgrade_role();
+        } else {
+            self.downgrade_role();
+        }
+    }
+
     // Go to a phase and return new phase, if successfully changed
     pub fn switch_phase(&mut self, desired_phase: PbftPhase) -> Option<PbftPhase> {
         let next = match self.phase {
@@ -252,6 +453,11 @@ impl PbftState {
         };
         if desired_phase == next {
             debug!("{}: Changing to {:?}", self, desired_phase);
+
+            let elapsed = self.phase_entered_at.elapsed();
+            *self.phase_totals.entry(self.phase.clone()).or_insert(Duration::default()) += elapsed;
+            self.phase_entered_at = Instant::now();
+
             self.phase = desired_phase.clone();
             Some(desired_phase)
         } else {
@@ -259,4 +465,177 @@ impl PbftState {
             None
         }
     }
+
+    // Cumulative time spent in each `PbftPhase` over this node's lifetime, including time spent
+    // in the phase it's currently in. Used to report "time spent in each PbftPhase" to operators
+    // via Prometheus.
+    pub fn phase_durations(&self) -> HashMap<PbftPhase, Duration> {
+        let mut totals = self.phase_totals.clone();
+        *totals.entry(self.phase.clone()).or_insert(Duration::default()) +=
+            self.phase_entered_at.elapsed();
+        totals
+    }
+
+    // Capture the fields needed to resume consensus after a restart. `certificates` and
+    // `stable_checkpoint_seq_num` come from the message log, which `PbftState` doesn't own, so
+    // the caller supplies them.
+    pub fn to_snapshot(&self, certificates: Vec<Vec<u8>>, stable_checkpoint_seq_num: u64) -> PbftSnapshot {
+        PbftSnapshot {
+            seq_num: self.seq_num,
+            view: self.view,
+            phase: self.phase.clone(),
+            mode: self.mode,
+            pre_checkpoint_mode: self.pre_checkpoint_mode,
+            working_block: self.working_block.clone(),
+            certificates,
+            stable_checkpoint_seq_num,
+        }
+    }
+
+    // Resume from a previously persisted snapshot, rather than starting from
+    // `PbftPhase::NotStarted`. The caller is responsible for replaying `snapshot.certificates`
+    // into the message log.
+    pub fn restore_from_snapshot(&mut self, snapshot: &PbftSnapshot) {
+        self.seq_num = snapshot.seq_num;
+        self.view = snapshot.view;
+        self.phase = snapshot.phase.clone();
+        self.mode = snapshot.mode;
+        self.pre_checkpoint_mode = snapshot.pre_checkpoint_mode;
+        self.working_block = snapshot.working_block.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 nodes, f = 1, satisfying the 3f+1 bound
+    fn four_node_config() -> PbftConfig {
+        PbftConfig::default_with_peers(vec![
+            (PeerId::from(vec![0x00]), 0),
+            (PeerId::from(vec![0x01]), 1),
+            (PeerId::from(vec![0x02]), 2),
+            (PeerId::from(vec![0x03]), 3),
+        ])
+    }
+
+    #[test]
+    fn add_node_inserts_and_recomputes_f_at_checkpoint_boundary() {
+        let mut state = PbftState::new(0, &four_node_config()).unwrap();
+        assert_eq!(state.f, 1);
+
+        state
+            .apply_membership_change(
+                &PbftMessageType::AddNode,
+                4,
+                Some(PeerId::from(vec![0x04])),
+                0,
+            )
+            .unwrap();
+
+        assert_eq!(state.peer_count(), 5);
+        assert_eq!(state.f, 1);
+    }
+
+    #[test]
+    fn add_node_rejects_duplicate_node_id() {
+        let mut state = PbftState::new(0, &four_node_config()).unwrap();
+
+        let result =
+            state.apply_membership_change(&PbftMessageType::AddNode, 1, Some(PeerId::from(vec![0xff])), 0);
+
+        assert!(result.is_err());
+        assert_eq!(state.peer_count(), 4);
+    }
+
+    #[test]
+    fn add_node_requires_a_peer_id() {
+        let mut state = PbftState::new(0, &four_node_config()).unwrap();
+
+        let result = state.apply_membership_change(&PbftMessageType::AddNode, 4, None, 0);
+
+        assert!(result.is_err());
+        assert_eq!(state.peer_count(), 4);
+    }
+
+    #[test]
+    fn remove_node_below_safety_threshold_is_rejected() {
+        // 4 nodes is exactly 3f+1 for f = 1; removing one would drop below it
+        let mut state = PbftState::new(0, &four_node_config()).unwrap();
+
+        let result = state.apply_membership_change(&PbftMessageType::RemoveNode, 3, None, 0);
+
+        assert!(result.is_err());
+        assert_eq!(state.peer_count(), 4);
+    }
+
+    #[test]
+    fn remove_node_above_threshold_succeeds() {
+        let mut config = four_node_config();
+        config.peers.push((PeerId::from(vec![0x04]), 4));
+        let mut state = PbftState::new(0, &config).unwrap();
+
+        state
+            .apply_membership_change(&PbftMessageType::RemoveNode, 4, None, 0)
+            .unwrap();
+
+        assert_eq!(state.peer_count(), 4);
+    }
+
+    #[test]
+    fn membership_change_rejected_off_checkpoint_boundary() {
+        let mut state = PbftState::new(0, &four_node_config()).unwrap();
+        state.seq_num = 50;
+
+        let result = state.apply_membership_change(
+            &PbftMessageType::AddNode,
+            4,
+            Some(PeerId::from(vec![0x04])),
+            100,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(state.peer_count(), 4);
+    }
+
+    #[test]
+    fn back_off_view_change_timeout_grows_and_counts() {
+        let mut state = PbftState::new(0, &four_node_config()).unwrap();
+        let base = state.timeout.duration();
+
+        state.back_off_view_change_timeout();
+        assert_eq!(state.consecutive_view_changes, 1);
+        assert_eq!(state.view_change_count, 1);
+        assert_eq!(state.timeout.duration(), base * 2);
+
+        state.back_off_view_change_timeout();
+        assert_eq!(state.consecutive_view_changes, 2);
+        assert_eq!(state.view_change_count, 2);
+        assert_eq!(state.timeout.duration(), base * 4);
+    }
+
+    #[test]
+    fn back_off_view_change_timeout_clamps_to_max() {
+        let mut config = four_node_config();
+        config.view_change_timeout = Duration::from_secs(10);
+        config.view_change_max_timeout = Duration::from_secs(15);
+        let mut state = PbftState::new(0, &config).unwrap();
+
+        state.back_off_view_change_timeout();
+        state.back_off_view_change_timeout();
+
+        assert_eq!(state.timeout.duration(), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn reset_view_change_backoff_restores_base_timeout() {
+        let mut state = PbftState::new(0, &four_node_config()).unwrap();
+        let base = state.timeout.duration();
+
+        state.back_off_view_change_timeout();
+        state.reset_view_change_backoff();
+
+        assert_eq!(state.consecutive_view_changes, 0);
+        assert_eq!(state.timeout.duration(), base);
+    }
 }
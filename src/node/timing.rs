@@ -0,0 +1,48 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::time::{Duration, Instant};
+
+// A restartable timer; used to detect that the primary has gone quiet and a view change is
+// needed
+#[derive(Debug, Clone)]
+pub struct Timeout {
+    duration: Duration,
+    start: Instant,
+}
+
+impl Timeout {
+    pub fn new(duration: Duration) -> Self {
+        Timeout {
+            duration,
+            start: Instant::now(),
+        }
+    }
+
+    // Restart the timer from now
+    pub fn start(&mut self) {
+        self.start = Instant::now();
+    }
+
+    pub fn check_expired(&self) -> bool {
+        self.start.elapsed() > self.duration
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}